@@ -2,8 +2,10 @@ use crate::webrtcsink::{Signallable, WebRTCSink};
 use gst::glib;
 use gst::subclass::prelude::ObjectSubclassExt;
 use std::error::Error;
+use std::sync::Arc;
 
 mod imp;
+mod offer;
 
 glib::wrapper! {
     pub struct Signaller(ObjectSubclass<imp::Signaller>);
@@ -12,6 +14,50 @@ glib::wrapper! {
 unsafe impl Send for Signaller {}
 unsafe impl Sync for Signaller {}
 
+#[derive(thiserror::Error, Debug)]
+pub enum SignallerError {
+    #[error("session already exists")]
+    SessionAlreadyExists { peer_id: String, location: Option<String> },
+    #[error("too many redirects")]
+    TooManyRedirects { peer_id: String, chain: Vec<String> },
+    #[error("no ICE candidates gathered")]
+    NoCandidates { peer_id: String },
+}
+
+/// An HTTP request made by the signaller, with the headers in the order
+/// they are sent
+#[derive(Debug, Clone, Default)]
+pub struct WhipRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// The response to a [`WhipRequest`]
+#[derive(Debug, Clone, Default)]
+pub struct WhipResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Carries the requests of the WHIP exchange, for applications that sign
+/// them or need a transport other than curl, and for tests. Implementations
+/// are written with `#[async_trait]`.
+///
+/// Redirects, conflicts and answers are still handled by the signaller, a
+/// transport only sends what it is given and returns what it receives
+#[surf::utils::async_trait]
+pub trait WhipTransport: Send + Sync + 'static {
+    /// POSTs an offer to the endpoint, or to where it redirected us
+    async fn post_offer(&self, request: WhipRequest) -> Result<WhipResponse, anyhow::Error>;
+    /// PATCHes an SDP fragment to a resource: trickled candidates, an ICE
+    /// restart or a renegotiation
+    async fn patch_candidates(&self, request: WhipRequest) -> Result<WhipResponse, anyhow::Error>;
+    /// DELETEs a resource
+    async fn delete_resource(&self, request: WhipRequest) -> Result<WhipResponse, anyhow::Error>;
+}
+
 impl Signallable for Signaller {
     fn start(&mut self, element: &WebRTCSink) -> Result<(), Box<dyn Error>> {
         let signaller = imp::Signaller::from_instance(self);
@@ -53,6 +99,151 @@ impl Signallable for Signaller {
         let signaller = imp::Signaller::from_instance(self);
         signaller.consumer_removed(element, peer_id);
     }
+
+    fn gathering_complete(&mut self, element: &WebRTCSink, peer_id: &str) {
+        let signaller = imp::Signaller::from_instance(self);
+        signaller.notify_gathering_complete(element, peer_id);
+    }
+
+    fn playing(&mut self, element: &WebRTCSink) {
+        let signaller = imp::Signaller::from_instance(self);
+        signaller.playing(element);
+    }
+
+    fn ice_connection_state(&mut self, element: &WebRTCSink, peer_id: &str, state: gst_webrtc::WebRTCICEConnectionState) {
+        let signaller = imp::Signaller::from_instance(self);
+        signaller.notify_ice_connection_state(element, peer_id, state);
+    }
+}
+
+/// Where a consumer is in the signalling flow, see [`Signaller::peer_state`]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerPeerState")]
+pub enum SignallerPeerState {
+    #[enum_value(name = "Offer pending: waiting for the sink to create an offer", nick = "offer-pending")]
+    OfferPending,
+    #[enum_value(name = "Gathering: collecting candidates for the offer", nick = "gathering")]
+    Gathering,
+    #[enum_value(name = "Posted: waiting for the server to answer", nick = "posted")]
+    Posted,
+    #[enum_value(name = "Established: the answer was applied", nick = "established")]
+    Established,
+    #[enum_value(name = "Failed: negotiation failed", nick = "failed")]
+    Failed,
+}
+
+/// Address family of the candidates advertised in the offer. This only
+/// shapes what is sent to the server, webrtcbin still gathers and checks
+/// candidates of every family internally.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerIpFamily")]
+pub enum SignallerIpFamily {
+    #[enum_value(name = "Any: advertise candidates of both families", nick = "any")]
+    Any,
+    #[enum_value(name = "IPv4: only advertise IPv4 candidates", nick = "ipv4")]
+    Ipv4,
+    #[enum_value(name = "IPv6: only advertise IPv6 candidates", nick = "ipv6")]
+    Ipv6,
+}
+
+/// What the signaller does with the candidates gathered after the offer
+/// was posted, see the late-candidate-policy property
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerLateCandidatePolicy")]
+pub enum SignallerLateCandidatePolicy {
+    #[enum_value(name = "Discard: drop late candidates", nick = "discard")]
+    Discard,
+    #[enum_value(name = "Trickle: PATCH late candidates to the resource", nick = "trickle")]
+    Trickle,
+    #[enum_value(name = "Error: post a warning for late candidates", nick = "error")]
+    Error,
+}
+
+/// What the signaller does with a new offer for a consumer it already has
+/// one for, when webrtcbin needs negotiation again, see the
+/// repeated-offer-policy property. Identical offers are always ignored
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerRepeatedOfferPolicy")]
+pub enum SignallerRepeatedOfferPolicy {
+    #[enum_value(name = "Ignore: keep the first offer", nick = "ignore")]
+    Ignore,
+    #[enum_value(
+        name = "Renegotiate: replace the offer if not posted yet, PATCH it to the resource otherwise",
+        nick = "renegotiate"
+    )]
+    Renegotiate,
+    #[enum_value(name = "Error: fail the consumer", nick = "error")]
+    Error,
+}
+
+/// When an offer is posted if gathering doesn't complete first, see the
+/// gather-timeout-mode property
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerGatherTimeoutMode")]
+pub enum SignallerGatherTimeoutMode {
+    #[enum_value(name = "Fixed: post a fixed time after the offer was created", nick = "fixed")]
+    Fixed,
+    #[enum_value(
+        name = "Adaptive: post once no server reflexive or relay candidate came for that time",
+        nick = "adaptive"
+    )]
+    Adaptive,
+}
+
+/// ICE role of the consumers, see the ice-role property
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSignallerIceRole")]
+pub enum SignallerIceRole {
+    #[enum_value(name = "Auto: let webrtcbin pick the role", nick = "auto")]
+    Auto,
+    #[enum_value(name = "Controlling: always act as the controlling agent", nick = "controlling")]
+    Controlling,
+    #[enum_value(name = "Controlled: always act as the controlled agent", nick = "controlled")]
+    Controlled,
+}
+
+impl Signaller {
+    /// The signalling state of `peer_id`, None for peers we don't know of
+    pub fn peer_state(&self, peer_id: &str) -> Option<SignallerPeerState> {
+        imp::Signaller::from_instance(self).peer_state(peer_id)
+    }
+
+    /// The last ICE connection state of `peer_id`, None until webrtcbin
+    /// reported one
+    pub fn ice_connection_state(&self, peer_id: &str) -> Option<gst_webrtc::WebRTCICEConnectionState> {
+        imp::Signaller::from_instance(self).ice_connection_state(peer_id)
+    }
+
+    /// Sends the WHIP requests through `transport` instead of the default
+    /// curl based client, from the next time the signaller is started.
+    /// This is how requests get signed, or mocked. The cafile property is
+    /// ignored then, TLS is up to `transport`
+    pub fn set_transport(&self, transport: impl WhipTransport) {
+        imp::Signaller::from_instance(self).set_transport(Arc::new(transport))
+    }
+
+    /// Stops sending media to `peer_id` while keeping its WHIP resource,
+    /// false if the peer is unknown or already paused
+    pub fn pause(&self, peer_id: &str) -> bool {
+        imp::Signaller::from_instance(self).pause(peer_id)
+    }
+
+    /// Resumes media paused with [`Signaller::pause`], without a new POST.
+    /// False if the peer wasn't paused
+    pub fn resume(&self, peer_id: &str) -> bool {
+        imp::Signaller::from_instance(self).resume(peer_id)
+    }
+
+    /// DELETEs the resources of all the consumers, leaving them connected
+    pub fn delete_all_resources(&self) {
+        imp::Signaller::from_instance(self).delete_all_resources()
+    }
 }
 
 impl Default for Signaller {
@@ -0,0 +1,417 @@
+use std::collections::{HashMap, HashSet};
+
+/// Whether `candidate` is a server reflexive or relay candidate
+pub fn is_nat_candidate(candidate: &str) -> bool {
+    let mut fields = candidate.split_whitespace().skip_while(|field| *field != "typ");
+    matches!(fields.nth(1), Some("srflx") | Some("relay"))
+}
+
+/// Assembles the offer posted to the WHIP server from the SDP created by
+/// webrtcbin and the candidates it gathers, in whatever order they come in
+#[derive(Debug, Default)]
+pub struct OfferBuilder {
+    sdp: Option<String>,
+    /// (m-line index, candidate) pairs, in gathering order
+    candidates: Vec<(u32, String)>,
+    /// Replaces the value of the s= line
+    session_name: Option<String>,
+    /// Replaces the username of the o= line
+    origin_username: Option<String>,
+    /// Bandwidth advertised for media sections, in bit/s, by media type
+    max_bitrates: HashMap<String, u32>,
+}
+
+impl OfferBuilder {
+    pub fn add_sdp(&mut self, sdp: &str) {
+        self.sdp = Some(sdp.to_string());
+    }
+
+    /// `candidate` is the value of the attribute, eg `candidate:1 1 UDP ...`.
+    /// Candidates may come in any order of `m_line`, and before or after the
+    /// SDP. Candidates already added for the same media section are ignored
+    pub fn add_candidate(&mut self, m_line: u32, candidate: &str) {
+        let duplicate = self
+            .candidates
+            .iter()
+            .any(|(idx, existing)| *idx == m_line && existing == candidate);
+
+        if !duplicate {
+            self.candidates.push((m_line, candidate.to_string()));
+        }
+    }
+
+    pub fn set_session_name(&mut self, session_name: Option<&str>) {
+        self.session_name = session_name.map(String::from);
+    }
+
+    pub fn set_origin_username(&mut self, username: Option<&str>) {
+        self.origin_username = username.map(String::from);
+    }
+
+    /// Advertises `bitrate` (in bit/s) as the bandwidth of the `media`
+    /// sections (eg `video`), replacing their b= lines. 0 leaves them as is
+    pub fn set_max_bitrate(&mut self, media: &str, bitrate: u32) {
+        if bitrate == 0 {
+            self.max_bitrates.remove(media);
+        } else {
+            self.max_bitrates.insert(media.to_string(), bitrate);
+        }
+    }
+
+    pub fn has_sdp(&self) -> bool {
+        self.sdp.is_some()
+    }
+
+    /// Whether a server reflexive or relay candidate was gathered, without
+    /// which peers behind a NAT can't be reached
+    pub fn has_nat_candidates(&self) -> bool {
+        self.candidates.iter().any(|(_, candidate)| is_nat_candidate(candidate))
+    }
+
+    /// Payload types and SSRCs of the SDP that don't add up: payload types
+    /// listed twice in a media section, rtpmap / fmtp lines for payload types
+    /// the section doesn't list or mapping one twice, dynamic payload types
+    /// without rtpmap, and SSRCs shared between media sections
+    pub fn anomalies(&self) -> Vec<String> {
+        let mut anomalies = Vec::new();
+        // SSRC -> index of the media section it was first seen in
+        let mut ssrcs: HashMap<&str, usize> = HashMap::new();
+
+        let mut sections: Vec<Vec<&str>> = Vec::new();
+        for line in self.sdp().lines() {
+            if line.starts_with("m=") {
+                sections.push(vec![line]);
+            } else if let Some(section) = sections.last_mut() {
+                section.push(line);
+            }
+        }
+
+        for (m_line, lines) in sections.iter().enumerate() {
+            let formats: Vec<&str> = lines[0].split_whitespace().skip(3).collect();
+            let mut listed = HashSet::new();
+            for pt in &formats {
+                if !listed.insert(*pt) {
+                    anomalies.push(format!("payload type {} listed twice in media section {}", pt, m_line));
+                }
+            }
+
+            let mut rtpmaps: HashMap<&str, &str> = HashMap::new();
+            for line in &lines[1..] {
+                if let Some(value) = line.strip_prefix("a=rtpmap:") {
+                    let (pt, encoding) = value.split_once(' ').unwrap_or((value, ""));
+
+                    if !listed.contains(pt) {
+                        anomalies.push(format!("rtpmap for unlisted payload type {} in media section {}", pt, m_line));
+                    }
+
+                    if let Some(previous) = rtpmaps.insert(pt, encoding) {
+                        anomalies.push(format!(
+                            "payload type {} of media section {} mapped twice, to {} and {}",
+                            pt, m_line, previous, encoding
+                        ));
+                    }
+                } else if let Some(value) = line.strip_prefix("a=fmtp:") {
+                    let (pt, params) = value.split_once(' ').unwrap_or((value, ""));
+
+                    if !listed.contains(pt) {
+                        anomalies.push(format!("fmtp for unlisted payload type {} in media section {}", pt, m_line));
+                    }
+
+                    for apt in params.split(';').filter_map(|param| param.trim().strip_prefix("apt=")) {
+                        if !listed.contains(apt) {
+                            anomalies.push(format!(
+                                "payload type {} of media section {} is associated with unlisted payload type {}",
+                                pt, m_line, apt
+                            ));
+                        }
+                    }
+                } else if let Some(value) = line.strip_prefix("a=ssrc:") {
+                    let ssrc = value.split_whitespace().next().unwrap_or_default();
+                    let first = *ssrcs.entry(ssrc).or_insert(m_line);
+
+                    if first != m_line {
+                        anomalies.push(format!("SSRC {} used in media sections {} and {}", ssrc, first, m_line));
+                        // Only report each section once
+                        ssrcs.insert(ssrc, m_line);
+                    }
+                }
+            }
+
+            for pt in &formats {
+                if pt.parse::<u8>().map_or(false, |pt| pt >= 96) && !rtpmaps.contains_key(pt) {
+                    anomalies.push(format!(
+                        "dynamic payload type {} of media section {} has no rtpmap",
+                        pt, m_line
+                    ));
+                }
+            }
+        }
+
+        anomalies
+    }
+
+    /// The SDP as created by webrtcbin, without the candidates
+    pub fn sdp(&self) -> &str {
+        self.sdp.as_deref().unwrap_or_default()
+    }
+
+    /// The offer with each candidate in its media section, and every section
+    /// terminated by end-of-candidates unless more candidates are to be
+    /// trickled. Candidates for media sections the SDP doesn't have are
+    /// dropped. The session name and origin username are overridden if set,
+    /// and so is the bandwidth of media sections, with b= lines placed after
+    /// the c= line as RFC 4566 orders them
+    pub fn finish(&self, end_of_candidates: bool) -> String {
+        let mut offer = String::new();
+        let mut m_line = None;
+        // Bandwidth to advertise for the current media section
+        let mut bitrate = None;
+
+        let mut with_connection = HashSet::new();
+        let mut idx = None;
+        for line in self.sdp().lines() {
+            if line.starts_with("m=") {
+                idx = Some(idx.map_or(0, |idx| idx + 1));
+            } else if line.starts_with("c=") {
+                with_connection.extend(idx);
+            }
+        }
+
+        let push_bandwidth = |offer: &mut String, bitrate: u32| {
+            offer.push_str(&format!("b=AS:{}\r\nb=TIAS:{}\r\n", (bitrate as u64).div_ceil(1000), bitrate));
+        };
+
+        let close_section = |offer: &mut String, m_line: Option<u32>| {
+            if let Some(m_line) = m_line {
+                for (_, candidate) in self.candidates.iter().filter(|(idx, _)| *idx == m_line) {
+                    offer.push_str("a=");
+                    offer.push_str(candidate);
+                    offer.push_str("\r\n");
+                }
+
+                if end_of_candidates {
+                    offer.push_str("a=end-of-candidates\r\n");
+                }
+            }
+        };
+
+        for line in self.sdp().lines().filter(|line| !line.is_empty()) {
+            if let Some(media) = line.strip_prefix("m=") {
+                close_section(&mut offer, m_line);
+                m_line = Some(m_line.map_or(0, |idx| idx + 1));
+
+                let kind = media.split_whitespace().next().unwrap_or_default();
+                bitrate = self.max_bitrates.get(kind).copied();
+            } else if bitrate.is_some() && (line.starts_with("b=AS:") || line.starts_with("b=TIAS:")) {
+                continue;
+            }
+
+            match (
+                line.split_once('='),
+                self.session_name.as_ref(),
+                self.origin_username.as_ref(),
+            ) {
+                (Some(("s", _)), Some(session_name), _) if m_line.is_none() => {
+                    offer.push_str("s=");
+                    offer.push_str(session_name);
+                }
+                (Some(("o", origin)), _, Some(username)) if m_line.is_none() => {
+                    offer.push_str("o=");
+                    offer.push_str(username);
+                    offer.push_str(origin.find(' ').map_or("", |idx| &origin[idx..]));
+                }
+                _ => offer.push_str(line),
+            }
+            offer.push_str("\r\n");
+
+            if let (Some(bitrate), Some(idx)) = (bitrate, m_line) {
+                let section_start = line.starts_with("m=") && !with_connection.contains(&idx);
+                if line.starts_with("c=") || section_start {
+                    push_bandwidth(&mut offer, bitrate);
+                }
+            }
+        }
+
+        close_section(&mut offer, m_line);
+
+        offer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDP: &str = "v=0\r\n\
+                       o=- 1 0 IN IP4 0.0.0.0\r\n\
+                       s=-\r\n\
+                       t=0 0\r\n\
+                       m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+                       c=IN IP4 0.0.0.0\r\n\
+                       a=mid:video0\r\n\
+                       a=rtpmap:96 VP8/90000\r\n\
+                       m=audio 9 UDP/TLS/RTP/SAVPF 97\r\n\
+                       c=IN IP4 0.0.0.0\r\n\
+                       a=mid:audio0\r\n\
+                       a=rtpmap:97 OPUS/48000/2\r\n";
+
+    /// The lines of each media section of `offer`
+    fn sections(offer: &str) -> Vec<Vec<&str>> {
+        let mut sections: Vec<Vec<&str>> = Vec::new();
+        for line in offer.split("\r\n").filter(|line| !line.is_empty()) {
+            if line.starts_with("m=") {
+                sections.push(Vec::new());
+            }
+            if let Some(section) = sections.last_mut() {
+                section.push(line);
+            }
+        }
+        sections
+    }
+
+    fn candidate(foundation: u32) -> String {
+        format!(
+            "candidate:{} 1 UDP 2122252543 192.168.1.{} 5000{} typ host",
+            foundation, foundation, foundation
+        )
+    }
+
+    #[test]
+    fn interleaved_candidates_land_in_their_section() {
+        let mut offer = OfferBuilder::default();
+        offer.add_candidate(1, &candidate(1));
+        offer.add_candidate(0, &candidate(2));
+        offer.add_sdp(SDP);
+        offer.add_candidate(1, &candidate(3));
+        offer.add_candidate(0, &candidate(4));
+
+        let finished = offer.finish(true);
+        assert!(finished.ends_with("\r\n"));
+
+        let sections = sections(&finished);
+        assert_eq!(sections.len(), 2);
+
+        let attributes: Vec<String> = (1..=4).map(|foundation| format!("a={}", candidate(foundation))).collect();
+        let (video, audio) = (&sections[0], &sections[1]);
+        assert_eq!(
+            video[video.len() - 3..],
+            [attributes[1].as_str(), attributes[3].as_str(), "a=end-of-candidates"]
+        );
+        assert_eq!(
+            audio[audio.len() - 3..],
+            [attributes[0].as_str(), attributes[2].as_str(), "a=end-of-candidates"]
+        );
+        assert_eq!(finished.matches("a=end-of-candidates").count(), 2);
+    }
+
+    #[test]
+    fn end_of_candidates_is_left_out_when_trickling() {
+        let mut offer = OfferBuilder::default();
+        offer.add_sdp(SDP);
+        offer.add_candidate(0, &candidate(1));
+
+        let finished = offer.finish(false);
+        assert!(!finished.contains("a=end-of-candidates"));
+
+        let sections = sections(&finished);
+        assert_eq!(sections[0].last(), Some(&format!("a={}", candidate(1)).as_str()));
+        assert_eq!(sections[1].last(), Some(&"a=rtpmap:97 OPUS/48000/2"));
+    }
+
+    #[test]
+    fn duplicate_and_unknown_section_candidates_are_dropped() {
+        let mut offer = OfferBuilder::default();
+        offer.add_sdp(SDP);
+        offer.add_candidate(0, &candidate(1));
+        offer.add_candidate(0, &candidate(1));
+        offer.add_candidate(1, &candidate(1));
+        offer.add_candidate(2, &candidate(2));
+
+        let finished = offer.finish(true);
+        let sections = sections(&finished);
+        let line = format!("a={}", candidate(1));
+        assert_eq!(sections[0].iter().filter(|l| **l == line).count(), 1);
+        assert_eq!(sections[1].iter().filter(|l| **l == line).count(), 1);
+        assert!(!finished.contains(&candidate(2)));
+    }
+
+    #[test]
+    fn finishing_leaves_the_builder_as_is() {
+        let mut offer = OfferBuilder::default();
+        assert!(!offer.has_sdp());
+        offer.add_sdp(SDP);
+        offer.add_candidate(0, &candidate(1));
+        assert!(offer.has_sdp());
+
+        // Candidates are trickled against the SDP webrtcbin created
+        let terminated = offer.finish(true);
+        assert_eq!(offer.finish(true), terminated);
+        assert_eq!(offer.sdp(), SDP);
+
+        offer.add_candidate(1, &candidate(2));
+        let trickling = offer.finish(false);
+        let sections = sections(&trickling);
+        assert_eq!(sections[1].last(), Some(&format!("a={}", candidate(2)).as_str()));
+    }
+
+    #[test]
+    fn second_sdp_replaces_the_first() {
+        // Which of the offers of a peer is kept is up to the signaller's
+        // repeated-offer-policy, replacing one keeps its candidates
+        let mut offer = OfferBuilder::default();
+        offer.add_sdp(SDP);
+        offer.add_candidate(1, &candidate(1));
+        offer.add_sdp(&SDP.replace("o=- 1 0", "o=- 1 1"));
+
+        let finished = offer.finish(true);
+        assert_eq!(finished.matches("v=0").count(), 1);
+        assert!(finished.contains("o=- 1 1 IN IP4"));
+        assert!(!finished.contains("o=- 1 0 IN IP4"));
+
+        let sections = sections(&finished);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1][sections[1].len() - 2], format!("a={}", candidate(1)));
+    }
+
+    #[test]
+    fn lines_end_with_crlf() {
+        let mut offer = OfferBuilder::default();
+        offer.add_sdp(&SDP.replace("\r\n", "\n"));
+        offer.add_candidate(0, &candidate(1));
+        offer.set_max_bitrate("video", 1_000_000);
+
+        let finished = offer.finish(true);
+        assert!(finished.ends_with("\r\n"));
+        assert_eq!(finished.matches('\n').count(), finished.matches("\r\n").count());
+        assert_eq!(finished.matches('\r').count(), finished.matches("\r\n").count());
+    }
+
+    #[test]
+    fn bandwidth_follows_the_connection_line() {
+        let mut offer = OfferBuilder::default();
+        offer.add_sdp(SDP);
+        offer.set_max_bitrate("video", 1_500_500);
+
+        let finished = offer.finish(true);
+        let sections = sections(&finished);
+        assert_eq!(
+            sections[0][..4],
+            [
+                "m=video 9 UDP/TLS/RTP/SAVPF 96",
+                "c=IN IP4 0.0.0.0",
+                "b=AS:1501",
+                "b=TIAS:1500500"
+            ]
+        );
+        assert!(!sections[1].iter().any(|line| line.starts_with("b=")));
+    }
+
+    #[test]
+    fn nat_candidates() {
+        assert!(!is_nat_candidate(&candidate(1)));
+        assert!(is_nat_candidate(
+            "candidate:2 1 UDP 1686052607 203.0.113.1 5000 typ srflx raddr 192.168.1.1 rport 5000"
+        ));
+        assert!(is_nat_candidate("candidate:3 1 UDP 41885439 198.51.100.1 5000 typ relay"));
+    }
+}
@@ -1787,6 +1787,12 @@ impl WebRTCSink {
                     .property::<gst_webrtc::WebRTCICEConnectionState>("ice-connection-state");
                 let this = Self::from_instance(&element);
 
+                this.state.lock().unwrap().signaller.ice_connection_state(
+                    &element,
+                    &peer_id_clone,
+                    state,
+                );
+
                 match state {
                     gst_webrtc::WebRTCICEConnectionState::Failed => {
                         gst::warning!(
@@ -1840,6 +1846,12 @@ impl WebRTCSink {
                     peer_id_clone,
                     state
                 );
+
+                if state == gst_webrtc::WebRTCICEGatheringState::Complete {
+                    let this = Self::from_instance(&element);
+                    let mut state = this.state.lock().unwrap();
+                    state.signaller.gathering_complete(&element, &peer_id_clone);
+                }
             }
         });
 
@@ -3033,6 +3045,7 @@ impl ElementImpl for WebRTCSink {
             gst::StateChange::PausedToPlaying => {
                 let mut state = self.state.lock().unwrap();
                 state.maybe_start_signaller(element);
+                state.signaller.playing(element);
             }
             _ => (),
         }
@@ -53,6 +53,25 @@ pub trait Signallable: Sync + Send + 'static {
 
     fn consumer_removed(&mut self, element: &WebRTCSink, peer_id: &str);
 
+    /// Called once webrtcbin is done gathering candidates for a consumer,
+    /// signallers that wait for all candidates before sending the offer
+    /// can use this instead of a timeout
+    fn gathering_complete(&mut self, _element: &WebRTCSink, _peer_id: &str) {}
+
+    /// Called when the sink goes to PLAYING, which may happen before or
+    /// after the signaller was started
+    fn playing(&mut self, _element: &WebRTCSink) {}
+
+    /// Called when the ICE connection state of a consumer changes, with
+    /// the sink state locked
+    fn ice_connection_state(
+        &mut self,
+        _element: &WebRTCSink,
+        _peer_id: &str,
+        _state: gst_webrtc::WebRTCICEConnectionState,
+    ) {
+    }
+
     fn stop(&mut self, element: &WebRTCSink);
 }
 
@@ -0,0 +1,417 @@
+//! Streams to a minimal WHIP endpoint that answers offers with a canned
+//! answer, and checks the signaller POSTs well-formed offers and DELETEs
+//! its resources. Like strict reverse proxies, the endpoint requires a
+//! `Content-Length` on offers and rejects chunked requests. The answer
+//! carries no candidates, only signalling is exercised.
+//!
+//! Needs GStreamer with the webrtc and videotestsrc plugins, run with
+//! `cargo test --test whip_signalling -- --ignored`
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Once};
+
+use anyhow::{anyhow, Error};
+
+use async_std::io::BufReader;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use futures::prelude::*;
+use gst::prelude::*;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::prelude::*;
+
+/// Number of video buffers to stream before shutting down
+const NUM_BUFFERS: u32 = 150;
+
+/// What the endpoint saw from the signaller
+#[derive(Default)]
+struct State {
+    /// Resources created by a POST and not DELETEd yet
+    resources: HashMap<String, String>,
+    /// Resources whose offer came without end-of-candidates, and that no
+    /// trickle PATCH terminated yet
+    unterminated: HashSet<String>,
+    /// Number of well-formed offers we answered
+    posts: u32,
+    /// Number of resources the signaller DELETEd
+    deletes: u32,
+    /// Everything that didn't behave like a WHIP client should
+    errors: Vec<String>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// Reads one HTTP/1.1 request, supporting only bodies with a Content-Length.
+/// Returns None once the client closed the connection
+async fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Option<Request>, Error> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| anyhow!("Missing method"))?.to_string();
+    let path = parts.next().ok_or_else(|| anyhow!("Missing path"))?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await?;
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let len = match headers.get("content-length") {
+        Some(len) => len.parse::<usize>()?,
+        None => 0,
+    };
+
+    if len > 0
+        && headers
+            .get("expect")
+            .map(|expect| expect.eq_ignore_ascii_case("100-continue"))
+            == Some(true)
+    {
+        reader.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, headers: &[(&str, &str)], body: &str) -> Result<(), Error> {
+    let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+    for (name, value) in headers {
+        response += &format!("{}: {}\r\n", name, value);
+    }
+    response += "\r\n";
+    response += body;
+
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+/// Builds an answer accepting every media section of the offer as is.
+/// The answer carries no candidates, the connection is not expected to
+/// succeed: only signalling is exercised
+fn canned_answer(offer: &gst_sdp::SDPMessage) -> Result<String, Error> {
+    let mut answer = gst_sdp::SDPMessageRef::to_owned(offer);
+
+    answer.set_origin("-", "1", "1", "IN", "IP4", "127.0.0.1");
+    answer.set_session_name("whip-mock-server");
+
+    for media in answer.medias_mut() {
+        for idx in (0..media.attributes_len()).rev() {
+            let key = media.attribute(idx).unwrap().key().to_string();
+
+            if matches!(
+                key.as_str(),
+                "ice-ufrag"
+                    | "ice-pwd"
+                    | "ice-options"
+                    | "fingerprint"
+                    | "setup"
+                    | "candidate"
+                    | "end-of-candidates"
+                    | "sendonly"
+                    | "sendrecv"
+                    | "recvonly"
+                    | "inactive"
+                    | "ssrc"
+                    | "ssrc-group"
+                    | "msid"
+            ) {
+                media.remove_attribute(idx)?;
+            }
+        }
+
+        media.add_attribute("ice-ufrag", Some("mock"));
+        media.add_attribute("ice-pwd", Some("mockmockmockmockmockmock"));
+        media.add_attribute(
+            "fingerprint",
+            Some(
+                "sha-256 00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF:\
+                 00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF",
+            ),
+        );
+        media.add_attribute("setup", Some("active"));
+        media.add_attribute("recvonly", None);
+    }
+
+    Ok(answer.as_text()?)
+}
+
+/// Checks the offer looks like what a WHIP client should POST, and returns
+/// it along with whether it carries end-of-candidates. Offers that don't
+/// must be terminated by a trickle PATCH
+fn check_offer(request: &Request) -> Result<(gst_sdp::SDPMessage, bool), Error> {
+    if !request.headers.contains_key("content-length") {
+        return Err(anyhow!("Offer was sent without a Content-Length"));
+    }
+
+    if request.headers.get("content-type").map(String::as_str) != Some("application/sdp") {
+        return Err(anyhow!(
+            "Unexpected Content-Type for the offer: {:?}",
+            request.headers.get("content-type")
+        ));
+    }
+
+    let offer = gst_sdp::SDPMessage::parse_buffer(&request.body).map_err(|err| anyhow!("Offer is not valid SDP: {}", err))?;
+
+    if offer.medias_len() == 0 {
+        return Err(anyhow!("Offer has no media section"));
+    }
+
+    let terminated: Vec<bool> = offer
+        .medias()
+        .map(|media| media.attributes().any(|attr| attr.key() == "end-of-candidates"))
+        .collect();
+    if terminated.contains(&true) && terminated.contains(&false) {
+        return Err(anyhow!("Offer is missing end-of-candidates in some sections"));
+    }
+
+    Ok((offer, terminated[0]))
+}
+
+async fn handle_request(state: &Arc<Mutex<State>>, stream: &mut TcpStream, request: Request) -> Result<(), Error> {
+    debug!("{} {}", request.method, request.path);
+
+    match request.method.as_str() {
+        "POST" => {
+            let answer = check_offer(&request).and_then(|(offer, terminated)| Ok((canned_answer(&offer)?, terminated)));
+
+            match answer {
+                Ok((answer, terminated)) => {
+                    let location = format!("/whip/resource/{}", uuid::Uuid::new_v4());
+
+                    {
+                        let mut state = state.lock().unwrap();
+                        state.posts += 1;
+                        state.resources.insert(location.clone(), answer.clone());
+                        if !terminated {
+                            state.unterminated.insert(location.clone());
+                        }
+                    }
+
+                    info!("Created resource {}", location);
+
+                    write_response(
+                        stream,
+                        "201 Created",
+                        &[("Content-Type", "application/sdp"), ("Location", &location)],
+                        &answer,
+                    )
+                    .await
+                }
+                Err(err) => {
+                    error!("Rejecting offer: {}", err);
+                    state.lock().unwrap().errors.push(err.to_string());
+                    write_response(stream, "400 Bad Request", &[], &err.to_string()).await
+                }
+            }
+        }
+        "DELETE" => {
+            if state.lock().unwrap().resources.remove(&request.path).is_some() {
+                info!("Deleted resource {}", request.path);
+                state.lock().unwrap().deletes += 1;
+                write_response(stream, "200 OK", &[], "").await
+            } else {
+                let err = format!("DELETE of unknown resource {}", request.path);
+                error!("{}", err);
+                state.lock().unwrap().errors.push(err);
+                write_response(stream, "404 Not Found", &[], "").await
+            }
+        }
+        "PATCH" => {
+            let known = state.lock().unwrap().resources.contains_key(&request.path);
+            let trickle = request.headers.get("content-type").map(String::as_str) == Some("application/trickle-ice-sdpfrag");
+
+            if known && trickle {
+                let fragment = String::from_utf8_lossy(&request.body);
+                let n_candidates = fragment.lines().filter(|line| line.starts_with("a=candidate:")).count();
+
+                info!("{} trickled {} candidate(s)", request.path, n_candidates);
+                if fragment.lines().any(|line| line == "a=end-of-candidates") {
+                    info!("Gathering for {} complete", request.path);
+                    state.lock().unwrap().unterminated.remove(&request.path);
+                }
+
+                write_response(stream, "204 No Content", &[], "").await
+            } else {
+                write_response(stream, "405 Method Not Allowed", &[], "").await
+            }
+        }
+        method => {
+            let err = format!("Unexpected method {}", method);
+            warn!("{}", err);
+            state.lock().unwrap().errors.push(err);
+            write_response(stream, "405 Method Not Allowed", &[], "").await
+        }
+    }
+}
+
+async fn accept_connection(state: Arc<Mutex<State>>, stream: TcpStream) {
+    let mut reader = BufReader::new(stream.clone());
+    let mut stream = stream;
+
+    loop {
+        match read_request(&mut reader).await {
+            Ok(Some(request)) if request.headers.contains_key("transfer-encoding") => {
+                // Like the strict reverse proxies some servers sit behind, we
+                // can't tell where such a body ends
+                let err = format!(
+                    "{} {} was sent with Transfer-Encoding: {}",
+                    request.method, request.path, request.headers["transfer-encoding"]
+                );
+                error!("Rejecting request: {}", err);
+                state.lock().unwrap().errors.push(err);
+                let _ = write_response(&mut stream, "411 Length Required", &[("Connection", "close")], "").await;
+                break;
+            }
+            Ok(Some(request)) => {
+                if let Err(err) = handle_request(&state, &mut stream, request).await {
+                    warn!("Failed to answer request: {}", err);
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                warn!("Failed to read request: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Streams to the endpoint at `port`, with the signaller trickling the
+/// candidates gathered after posting if `trickle` is set
+async fn run_pipeline(port: u16, trickle: bool) -> Result<(), Error> {
+    let pipeline_str = format!(
+        "webrtcsink name=ws signaller::address=http://127.0.0.1:{}/whip \
+         signaller::late-candidate-policy={} \
+         videotestsrc is-live=true num-buffers={} ! video/x-raw,width=640,height=480 ! ws.video_0",
+        port,
+        if trickle { "trickle" } else { "discard" },
+        NUM_BUFFERS
+    );
+
+    let pipeline = gst::parse_launch(&pipeline_str)?;
+    let mut messages = pipeline.bus().unwrap().stream();
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    while let Some(msg) = messages.next().await {
+        match msg.view() {
+            gst::MessageView::Eos(..) => break,
+            gst::MessageView::Error(err) => {
+                error!("Pipeline error: {}", err.error());
+                break;
+            }
+            gst::MessageView::Application(app) => {
+                if let Some(s) = app.structure() {
+                    debug!("{}", s);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Tearing down the sink makes the signaller DELETE its resources
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
+
+fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        tracing_log::LogTracer::init().expect("Failed to set logger");
+        let env_filter = tracing_subscriber::EnvFilter::try_from_env("WHIP_MOCK_SERVER_LOG")
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+        let fmt_layer = tracing_subscriber::fmt::layer().with_thread_ids(true).with_target(true);
+        let subscriber = tracing_subscriber::Registry::default().with(env_filter).with(fmt_layer);
+        tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
+
+        gst::init().unwrap();
+        webrtcsink::plugin_register_static().unwrap();
+    });
+}
+
+async fn run(trickle: bool) -> Result<(), Error> {
+    let state = Arc::new(Mutex::new(State::default()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind");
+    let port = listener.local_addr()?.port();
+    info!("Listening on port {}", port);
+
+    let state_clone = state.clone();
+    task::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            task::spawn(accept_connection(state_clone.clone(), stream));
+        }
+    });
+
+    run_pipeline(port, trickle).await?;
+
+    let state = state.lock().unwrap();
+
+    info!(
+        "{} offer(s) answered, {} resource(s) deleted, {} left over",
+        state.posts,
+        state.deletes,
+        state.resources.len()
+    );
+
+    if let Some(err) = state.errors.first() {
+        return Err(anyhow!("Signaller misbehaved: {}", err));
+    }
+
+    if state.posts == 0 {
+        return Err(anyhow!("Signaller never POSTed an offer"));
+    }
+
+    if !state.unterminated.is_empty() {
+        return Err(anyhow!("Signaller never sent end-of-candidates for {:?}", state.unterminated));
+    }
+
+    if !state.resources.is_empty() {
+        return Err(anyhow!("Signaller didn't DELETE {:?}", state.resources.keys()));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs GStreamer with the webrtc and videotestsrc plugins"]
+fn offers_are_posted_and_deleted() {
+    init();
+    task::block_on(run(false)).unwrap();
+}
+
+#[test]
+#[ignore = "needs GStreamer with the webrtc and videotestsrc plugins"]
+fn late_candidates_are_trickled() {
+    init();
+    task::block_on(run(true)).unwrap();
+}